@@ -0,0 +1,183 @@
+//! A fairing that decompresses incoming request bodies.
+
+use rocket::{
+    data::{ByteUnit, Data, ToByteUnit},
+    fairing::{Fairing, Info, Kind},
+    tokio::io::AsyncReadExt,
+    Request,
+};
+
+use crate::Encoding;
+
+const CONTENT_ENCODING: &str = "content-encoding";
+
+/// Transparently decompresses request bodies that carry a `Content-Encoding`
+/// header, so handlers always see plain bytes.
+///
+/// Multiple, chained codings (e.g. `Content-Encoding: gzip, br`) are undone
+/// in reverse order, matching the order in which they were applied.
+///
+/// # Usage
+///
+/// ```rust
+/// use rocket_async_compression::Decompression;
+///
+/// # let _ = async {
+/// rocket::build()
+///     .attach(Decompression::fairing())
+///     .launch()
+///     .await?;
+/// # Ok::<_, rocket::Error>(())
+/// # };
+/// ```
+pub struct Decompression {
+    limit: ByteUnit,
+}
+
+impl Decompression {
+    /// Returns a fairing that decompresses request bodies, capping each
+    /// coding's *decompressed* output at 10 MiB to guard against
+    /// decompression bombs.
+    pub fn fairing() -> impl Fairing {
+        Self::default()
+    }
+
+    /// Whether `encoding` is one [`Decompression::decode`] actually knows how
+    /// to undo.
+    fn is_supported(encoding: &Encoding) -> bool {
+        match encoding {
+            Encoding::Gzip | Encoding::Brotli | Encoding::Deflate => true,
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => true,
+            _ => false,
+        }
+    }
+
+    /// Decodes `body`, which was compressed with `encoding`, capping the
+    /// decompressed output at `limit` bytes to guard against decompression
+    /// bombs.
+    ///
+    /// Only call this with an `encoding` for which [`Decompression::is_supported`]
+    /// returns `true`.
+    async fn decode(encoding: &Encoding, body: Vec<u8>, limit: ByteUnit) -> std::io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        match encoding {
+            Encoding::Gzip => {
+                let decoder = async_compression::tokio::bufread::GzipDecoder::new(body.as_slice());
+                Self::copy_capped(decoder, &mut out, limit).await?;
+            }
+            Encoding::Brotli => {
+                let decoder =
+                    async_compression::tokio::bufread::BrotliDecoder::new(body.as_slice());
+                Self::copy_capped(decoder, &mut out, limit).await?;
+            }
+            Encoding::Deflate => {
+                let decoder = async_compression::tokio::bufread::ZlibDecoder::new(body.as_slice());
+                Self::copy_capped(decoder, &mut out, limit).await?;
+            }
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => {
+                let decoder = async_compression::tokio::bufread::ZstdDecoder::new(body.as_slice());
+                Self::copy_capped(decoder, &mut out, limit).await?;
+            }
+            _ => unreachable!("Decompression::is_supported already rejected this encoding"),
+        }
+        Ok(out)
+    }
+
+    /// Copies `reader` into `out`, failing instead of reading past `limit`
+    /// decompressed bytes.
+    async fn copy_capped<R: rocket::tokio::io::AsyncRead + Unpin>(
+        reader: R,
+        out: &mut Vec<u8>,
+        limit: ByteUnit,
+    ) -> std::io::Result<()> {
+        // Read one byte past the limit so we can tell "exactly at the limit"
+        // apart from "there was more data we refused to read".
+        let mut limited = reader.take(limit.as_u64() + 1);
+        rocket::tokio::io::copy(&mut limited, out).await?;
+
+        if out.len() as u64 > limit.as_u64() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "decompressed request body exceeds the configured size limit",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Decompression {
+    fn default() -> Self {
+        Decompression {
+            limit: 10.mebibytes(),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Decompression {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request Decompression",
+            kind: Kind::Request,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, data: &mut Data<'_>) {
+        let codings: Vec<Encoding> = request
+            .headers()
+            .get(CONTENT_ENCODING)
+            .flat_map(|value| value.split(','))
+            .map(|coding| coding.trim().parse().unwrap())
+            .filter(|encoding| !matches!(encoding, Encoding::Identity))
+            .collect();
+
+        if codings.is_empty() {
+            return;
+        }
+
+        if !codings.iter().all(Self::is_supported) {
+            // We can't safely decode this body: leave the request
+            // untouched (including its `Content-Encoding` header) rather
+            // than feed handlers bytes we only pretended to decompress.
+            return;
+        }
+
+        let taken = std::mem::replace(data, Data::local(Vec::new()));
+        let capped = match taken.open(self.limit).into_bytes().await {
+            Ok(capped) if capped.is_complete() => capped,
+            Ok(capped) => {
+                // The body was too large to read in full: we can't recover
+                // the bytes past `self.limit` we refused to read, but we can
+                // at least avoid compounding that with a falsely-successful
+                // `Content-Encoding`, so leave the header alone.
+                *data = Data::local(capped.into_inner());
+                return;
+            }
+            Err(_) => return,
+        };
+
+        // Keep the still-encoded bytes around so a failed decode can restore
+        // them instead of leaving `*data` as the empty placeholder above
+        // while claiming, via the untouched `Content-Encoding` header, that
+        // it still holds compressed data.
+        let original = capped.into_inner();
+        let mut decoded = original.clone();
+        // `Content-Encoding` lists codings in the order they were applied,
+        // so they must be undone in reverse.
+        for encoding in codings.iter().rev() {
+            decoded = match Self::decode(encoding, decoded, self.limit).await {
+                Ok(decoded) => decoded,
+                Err(_) => {
+                    *data = Data::local(original);
+                    return;
+                }
+            };
+        }
+
+        request.headers_mut().remove(CONTENT_ENCODING);
+        *data = Data::local(decoded);
+    }
+}
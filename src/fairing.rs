@@ -0,0 +1,365 @@
+//! Fairings that compress outgoing responses.
+
+use std::path::PathBuf;
+
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::MediaType,
+    Request, Response,
+};
+
+use crate::{CompressionUtils, Encoding};
+
+/// The subset of [`Encoding`]s that the fairings in this module know how to
+/// produce and cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CachedEncoding {
+    Brotli,
+    Gzip,
+    Deflate,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl CachedEncoding {
+    fn encoding(self) -> Encoding {
+        match self {
+            CachedEncoding::Brotli => Encoding::Brotli,
+            CachedEncoding::Gzip => Encoding::Gzip,
+            CachedEncoding::Deflate => Encoding::Deflate,
+            #[cfg(feature = "zstd")]
+            CachedEncoding::Zstd => Encoding::Zstd,
+        }
+    }
+
+    /// The file extension of the on-disk pre-compressed sibling file for
+    /// this encoding, e.g. `app.js` -> `app.js.br`.
+    fn sibling_extension(self) -> &'static str {
+        match self {
+            CachedEncoding::Brotli => "br",
+            CachedEncoding::Gzip => "gz",
+            CachedEncoding::Deflate => "zz",
+            #[cfg(feature = "zstd")]
+            CachedEncoding::Zstd => "zst",
+        }
+    }
+}
+
+/// Media types that are already compressed, or otherwise not worth
+/// compressing again, excluded from [`Compression`] by default.
+fn default_exclusions() -> Vec<MediaType> {
+    vec![
+        MediaType::new("image", "*"),
+        MediaType::new("video", "*"),
+        MediaType::new("audio", "*"),
+        MediaType::new("application", "gzip"),
+        MediaType::new("application", "zip"),
+        MediaType::new("application", "x-bzip2"),
+        MediaType::new("application", "x-7z-compressed"),
+        MediaType::new("application", "font-woff2"),
+        MediaType::new("font", "woff2"),
+        MediaType::new("image", "png"),
+        MediaType::new("image", "jpeg"),
+    ]
+}
+
+/// Responses smaller than this, in bytes, are left uncompressed by default:
+/// the framing and CPU overhead of compression outweighs the savings.
+const DEFAULT_MINIMUM_SIZE: usize = 1024;
+
+/// Compresses all responses that haven't already been encoded, based on the
+/// `Accept-Encoding` header of the request.
+///
+/// # Usage
+///
+/// Attach the fairing to your Rocket application:
+///
+/// ```rust
+/// use rocket_async_compression::Compression;
+///
+/// # let _ = async {
+/// rocket::build()
+///     .attach(Compression::fairing())
+///     .launch()
+///     .await?;
+/// # Ok::<_, rocket::Error>(())
+/// # };
+/// ```
+pub struct Compression {
+    exclusions: Vec<MediaType>,
+    minimum_size: usize,
+    level: async_compression::Level,
+}
+
+impl Compression {
+    /// Returns a fairing for response compression with the default
+    /// exclusions, minimum size and compression level.
+    ///
+    /// Returns `Self` rather than `impl Fairing` so it can be further
+    /// configured with [`Compression::exclude`] and
+    /// [`Compression::minimum_size`] before being attached.
+    pub fn fairing() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the list of media types excluded from compression (see
+    /// [`default_exclusions`] for the defaults), supporting `top/*` wildcards.
+    pub fn exclude(mut self, exclusions: Vec<MediaType>) -> Self {
+        self.exclusions = exclusions;
+        self
+    }
+
+    /// Sets the minimum response body size, in bytes, below which responses
+    /// are left uncompressed. Responses with an unknown size are always
+    /// considered for compression.
+    pub fn minimum_size(mut self, minimum_size: usize) -> Self {
+        self.minimum_size = minimum_size;
+        self
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression {
+            exclusions: default_exclusions(),
+            minimum_size: DEFAULT_MINIMUM_SIZE,
+            level: async_compression::Level::Default,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Compression {
+    fn info(&self) -> Info {
+        Info {
+            name: "Response Compression",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        CompressionUtils::compress_response(
+            request,
+            response,
+            &self.exclusions,
+            self.minimum_size,
+            self.level,
+        );
+    }
+}
+
+/// Serves a cached, compressed copy of responses whose request path matches
+/// one of a configured list of suffixes, compressing (and caching, in
+/// memory) the body the first time each path/encoding pair is requested.
+///
+/// Call [`CachedCompression::precompressed`] to instead serve a
+/// pre-compressed sibling file from disk (e.g. `app.js.br` next to
+/// `app.js`), for assets that are already compressed at deploy time.
+pub struct CachedCompression {
+    suffixes: Vec<String>,
+    level: async_compression::Level,
+    precompressed_root: Option<PathBuf>,
+    cache: rocket::tokio::sync::Mutex<std::collections::HashMap<(String, CachedEncoding), Vec<u8>>>,
+}
+
+impl CachedCompression {
+    /// Builds the suffix list expected by [`CachedCompression::path_suffix_fairing`]
+    /// from a list of file extensions, e.g. `vec![".txt", ".html"]`.
+    pub fn static_paths(suffixes: Vec<impl Into<String>>) -> Vec<String> {
+        suffixes.into_iter().map(Into::into).collect()
+    }
+
+    /// Returns a fairing that compresses (and caches) responses whose request
+    /// path ends with one of `suffixes`.
+    pub fn path_suffix_fairing(suffixes: Vec<String>) -> Self {
+        CachedCompression {
+            suffixes,
+            level: async_compression::Level::Default,
+            precompressed_root: None,
+            cache: Default::default(),
+        }
+    }
+
+    /// Marks this fairing's paths as already compressed on disk: instead of
+    /// compressing the response body itself, it looks for a sibling file
+    /// named `<served path>.<br|gz|zz|zst>` under `root` and serves that
+    /// verbatim with the matching `Content-Encoding`, falling back to
+    /// leaving the response untouched if no such file exists.
+    pub fn precompressed(mut self, root: impl Into<PathBuf>) -> Self {
+        self.precompressed_root = Some(root.into());
+        self
+    }
+
+    fn matches(&self, request: &Request<'_>) -> bool {
+        let path = request.uri().path();
+        self.suffixes
+            .iter()
+            .any(|suffix| path.as_str().ends_with(suffix.as_str()))
+    }
+
+    async fn serve_precompressed<'r>(
+        root: &std::path::Path,
+        request: &Request<'_>,
+        response: &mut Response<'r>,
+        encoding: CachedEncoding,
+    ) -> bool {
+        let sibling = match sanitize_path(root, request.uri().path().as_str()) {
+            Some(path) => path,
+            None => return false,
+        };
+
+        let mut sibling = sibling.into_os_string();
+        sibling.push(".");
+        sibling.push(encoding.sibling_extension());
+
+        match rocket::tokio::fs::File::open(PathBuf::from(sibling)).await {
+            Ok(file) => {
+                CompressionUtils::set_body_and_encoding(response, file, encoding.encoding());
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// Joins `uri_path` onto `root`: skips empty and `.` segments, and returns
+/// `None` for any `..` segment or segment containing a path separator, so a
+/// request path can never escape `root`.
+fn sanitize_path(root: &std::path::Path, uri_path: &str) -> Option<PathBuf> {
+    let mut buf = root.to_path_buf();
+    for segment in uri_path.split('/') {
+        if segment.is_empty() || segment == "." {
+            continue;
+        }
+        if segment == ".." || segment.contains(std::path::is_separator) {
+            return None;
+        }
+        buf.push(segment);
+    }
+    Some(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_a_plain_path_onto_root() {
+        let root = std::path::Path::new("/srv/static");
+        assert_eq!(
+            sanitize_path(root, "/app.js"),
+            Some(root.join("app.js"))
+        );
+        assert_eq!(
+            sanitize_path(root, "/assets/app.js"),
+            Some(root.join("assets").join("app.js"))
+        );
+    }
+
+    #[test]
+    fn skips_empty_and_dot_segments() {
+        let root = std::path::Path::new("/srv/static");
+        assert_eq!(
+            sanitize_path(root, "//./assets//app.js"),
+            Some(root.join("assets").join("app.js"))
+        );
+    }
+
+    #[test]
+    fn rejects_dot_dot_segments() {
+        let root = std::path::Path::new("/srv/static");
+        assert_eq!(sanitize_path(root, "/../etc/passwd"), None);
+        assert_eq!(sanitize_path(root, "/assets/../../etc/passwd"), None);
+    }
+
+    #[test]
+    fn rejects_a_path_separator_embedded_in_a_segment() {
+        // `uri_path` is only ever split on `/`, so on platforms with another
+        // path separator (e.g. `\` on Windows) a single segment could still
+        // smuggle it through; `sanitize_path` must reject that regardless.
+        let root = std::path::Path::new("/srv/static");
+        let smuggled = format!("a{}b", std::path::MAIN_SEPARATOR);
+
+        if cfg!(windows) {
+            assert_eq!(sanitize_path(root, &smuggled), None);
+        } else {
+            // On Unix the only separator is `/`, which is already split on
+            // above, so this is indistinguishable from the plain `a/b`.
+            assert_eq!(
+                sanitize_path(root, &smuggled),
+                Some(root.join("a").join("b"))
+            );
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for CachedCompression {
+    fn info(&self) -> Info {
+        Info {
+            name: "Cached Compression",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if !self.matches(request) {
+            return;
+        }
+
+        if CompressionUtils::already_encoded(response) {
+            // Matches `CompressionUtils::compress_response`: a response that
+            // passes through already content-coded still varies by this
+            // header.
+            CompressionUtils::add_vary_header(response);
+            return;
+        }
+
+        let encoding = match CompressionUtils::best_encoding(request) {
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => CachedEncoding::Zstd,
+            Encoding::Brotli => CachedEncoding::Brotli,
+            Encoding::Gzip => CachedEncoding::Gzip,
+            Encoding::Deflate => CachedEncoding::Deflate,
+            _ => return,
+        };
+
+        if let Some(root) = &self.precompressed_root {
+            if Self::serve_precompressed(root, request, response, encoding).await {
+                CompressionUtils::add_vary_header(response);
+            }
+            return;
+        }
+
+        let key = (request.uri().path().to_string(), encoding);
+        let mut cache = self.cache.lock().await;
+        let compressed = match cache.get(&key) {
+            Some(compressed) => compressed.clone(),
+            None => {
+                // `compress_body` consumes the body and does real I/O that
+                // can fail; the original body is gone by then, so a failure
+                // is answered with a 500 and an empty body.
+                let body = response.body_mut().take();
+                match CompressionUtils::compress_body(body, encoding, self.level).await {
+                    Ok(compressed) => {
+                        cache.insert(key, compressed.clone());
+                        compressed
+                    }
+                    Err(_) => {
+                        drop(cache);
+                        response.set_status(rocket::http::Status::InternalServerError);
+                        return;
+                    }
+                }
+            }
+        };
+        drop(cache);
+
+        CompressionUtils::set_body_and_encoding(
+            response,
+            std::io::Cursor::new(compressed),
+            encoding.encoding(),
+        );
+        CompressionUtils::add_vary_header(response);
+    }
+}
@@ -28,10 +28,12 @@
 //! application vulnerable to attacks including BREACH. These risks should be
 //! evaluated in the context of your application before enabling compression.
 
+mod decompression;
 mod fairing;
 mod responder;
 
 pub use self::{
+    decompression::Decompression,
     fairing::{CachedCompression, Compression},
     responder::Compress,
 };
@@ -49,6 +51,9 @@ pub enum Encoding {
     Brotli,
     /// The `gzip` encoding.
     Gzip,
+    /// The `zstd` encoding.
+    #[cfg(feature = "zstd")]
+    Zstd,
     /// The `deflate` encoding.
     Deflate,
     /// The `compress` encoding.
@@ -67,6 +72,8 @@ impl std::fmt::Display for Encoding {
             Encoding::Chunked => "chunked",
             Encoding::Brotli => "br",
             Encoding::Gzip => "gzip",
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => "zstd",
             Encoding::Deflate => "deflate",
             Encoding::Compress => "compress",
             Encoding::Identity => "identity",
@@ -85,6 +92,8 @@ impl std::str::FromStr for Encoding {
             "br" => Ok(Encoding::Brotli),
             "deflate" => Ok(Encoding::Deflate),
             "gzip" => Ok(Encoding::Gzip),
+            #[cfg(feature = "zstd")]
+            "zstd" => Ok(Encoding::Zstd),
             "compress" => Ok(Encoding::Compress),
             "identity" => Ok(Encoding::Identity),
             "trailers" => Ok(Encoding::Trailers),
@@ -112,6 +121,30 @@ impl CompressionUtils {
         response.set_streamed_body(body);
     }
 
+    /// Merges `Accept-Encoding` into the response's `Vary` header instead of
+    /// overwriting any existing value, so caches know the response varies by
+    /// the negotiated content-coding.
+    fn add_vary_header(response: &mut Response<'_>) {
+        const VARY: &str = "vary";
+
+        let merged = match response.headers().get_one(VARY) {
+            Some(existing) => {
+                let already_present = existing
+                    .split(',')
+                    .any(|value| value.trim().eq_ignore_ascii_case("accept-encoding"));
+
+                if already_present {
+                    return;
+                }
+
+                format!("{existing}, Accept-Encoding")
+            }
+            None => "Accept-Encoding".to_owned(),
+        };
+
+        response.set_header(::rocket::http::Header::new(VARY, merged));
+    }
+
     fn skip_encoding(
         content_type: &Option<rocket::http::ContentType>,
         exclusions: &[MediaType],
@@ -128,19 +161,82 @@ impl CompressionUtils {
         }
     }
 
-    /// Returns a tuple of the form (accepts_gzip, accepts_br).
-    fn accepted_algorithms(request: &Request<'_>) -> (bool, bool) {
-        request
-            .headers()
-            .get("Accept-Encoding")
-            .flat_map(|accept| accept.split(','))
-            .map(|accept| accept.trim())
-            .fold((false, false), |(accepts_gzip, accepts_br), encoding| {
-                (
-                    accepts_gzip || encoding == "gzip",
-                    accepts_br || encoding == "br",
-                )
+    /// Parses the `Accept-Encoding` header(s) of `request` into `(coding, q)`
+    /// pairs, defaulting missing `;q=` weights to `1.0` and clamping them to
+    /// `[0, 1]`.
+    fn parse_accept_encoding(request: &Request<'_>) -> Vec<(String, f32)> {
+        Self::parse_accept_encoding_values(request.headers().get("Accept-Encoding"))
+    }
+
+    /// The actual, header-value-agnostic parsing logic behind
+    /// [`CompressionUtils::parse_accept_encoding`], split out so it can be
+    /// exercised directly in tests without building a [`Request`].
+    fn parse_accept_encoding_values<'a>(
+        values: impl Iterator<Item = &'a str>,
+    ) -> Vec<(String, f32)> {
+        values
+            .flat_map(|value| value.split(','))
+            .filter_map(|item| {
+                let mut parts = item.split(';');
+                let coding = parts.next()?.trim().to_ascii_lowercase();
+                if coding.is_empty() {
+                    return None;
+                }
+
+                let q = parts
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .and_then(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0)
+                    .clamp(0.0, 1.0);
+
+                Some((coding, q))
             })
+            .collect()
+    }
+
+    /// Looks up the quality value a client assigned to `coding`, falling back
+    /// to the `*` wildcard when `coding` isn't mentioned explicitly.
+    fn quality_of(accepted: &[(String, f32)], coding: &str) -> Option<f32> {
+        accepted
+            .iter()
+            .find(|(c, _)| c == coding)
+            .or_else(|| accepted.iter().find(|(c, _)| c == "*"))
+            .map(|(_, q)| *q)
+    }
+
+    /// Negotiates the single best encoding to use for `request`, among the
+    /// encodings the server is able to produce, honoring `;q=` weights, the
+    /// `*` wildcard and explicit `q=0` refusals. Ties are broken by a fixed
+    /// server preference order (zstd, then brotli, then gzip, then deflate). Returns
+    /// `Encoding::Identity` if no acceptable coding remains.
+    fn best_encoding(request: &Request<'_>) -> Encoding {
+        Self::best_encoding_for(Self::parse_accept_encoding(request))
+    }
+
+    /// The actual negotiation logic behind [`CompressionUtils::best_encoding`],
+    /// taking already-parsed `(coding, q)` pairs so it can be tested without
+    /// building a [`Request`].
+    fn best_encoding_for(accepted: Vec<(String, f32)>) -> Encoding {
+        if accepted.is_empty() {
+            return Encoding::Identity;
+        }
+
+        let mut best: Option<(Encoding, f32)> = None;
+        let mut consider = |coding: &str, encoding: Encoding| {
+            if let Some(q) = Self::quality_of(&accepted, coding) {
+                if q > 0.0 && best.as_ref().map_or(true, |(_, best_q)| q > *best_q) {
+                    best = Some((encoding, q));
+                }
+            }
+        };
+
+        #[cfg(feature = "zstd")]
+        consider("zstd", Encoding::Zstd);
+        consider("br", Encoding::Brotli);
+        consider("gzip", Encoding::Gzip);
+        consider("deflate", Encoding::Deflate);
+
+        best.map(|(encoding, _)| encoding).unwrap_or(Encoding::Identity)
     }
 
     async fn compress_body<'r>(
@@ -176,6 +272,25 @@ impl CompressionUtils {
                 rocket::tokio::io::copy(&mut compressor, &mut out).await?;
                 Ok(out)
             }
+            CachedEncoding::Deflate => {
+                let mut compressor = async_compression::tokio::bufread::ZlibEncoder::with_quality(
+                    rocket::tokio::io::BufReader::new(body),
+                    level,
+                );
+                let mut out = Vec::new();
+                rocket::tokio::io::copy(&mut compressor, &mut out).await?;
+                Ok(out)
+            }
+            #[cfg(feature = "zstd")]
+            CachedEncoding::Zstd => {
+                let mut compressor = async_compression::tokio::bufread::ZstdEncoder::with_quality(
+                    rocket::tokio::io::BufReader::new(body),
+                    level,
+                );
+                let mut out = Vec::new();
+                rocket::tokio::io::copy(&mut compressor, &mut out).await?;
+                Ok(out)
+            }
         }
     }
 
@@ -183,9 +298,14 @@ impl CompressionUtils {
         request: &Request<'_>,
         response: &'_ mut Response<'r>,
         exclusions: &[MediaType],
+        minimum_size: usize,
         level: async_compression::Level,
     ) {
         if CompressionUtils::already_encoded(response) {
+            // The response is already content-coded (e.g. the handler set
+            // its own `Content-Encoding`): pass it through untouched, but
+            // still let caches know the response varies by this header.
+            CompressionUtils::add_vary_header(response);
             return;
         }
 
@@ -195,29 +315,116 @@ impl CompressionUtils {
             return;
         }
 
-        let (accepts_gzip, accepts_br) = Self::accepted_algorithms(request);
+        // Bodies with a known size below the threshold aren't worth the
+        // framing and CPU overhead of compression. Bodies of unknown size
+        // (streamed responses) are always considered.
+        if let Some(size) = response.body().size() {
+            if (size as usize) < minimum_size {
+                return;
+            }
+        }
 
-        if !accepts_gzip && !accepts_br {
+        let encoding = Self::best_encoding(request);
+        if matches!(encoding, Encoding::Identity) {
             return;
         }
 
         let body = response.body_mut().take();
 
-        // Compression is done when the request accepts brotli or gzip encoding
-        if accepts_br {
-            let compressor = async_compression::tokio::bufread::BrotliEncoder::with_quality(
-                rocket::tokio::io::BufReader::new(body),
-                level,
-            );
-
-            CompressionUtils::set_body_and_encoding(response, compressor, Encoding::Brotli);
-        } else if accepts_gzip {
-            let compressor = async_compression::tokio::bufread::GzipEncoder::with_quality(
-                rocket::tokio::io::BufReader::new(body),
-                level,
-            );
-
-            CompressionUtils::set_body_and_encoding(response, compressor, Encoding::Gzip);
+        match encoding {
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => {
+                let compressor = async_compression::tokio::bufread::ZstdEncoder::with_quality(
+                    rocket::tokio::io::BufReader::new(body),
+                    level,
+                );
+
+                CompressionUtils::set_body_and_encoding(response, compressor, Encoding::Zstd);
+            }
+            Encoding::Brotli => {
+                let compressor = async_compression::tokio::bufread::BrotliEncoder::with_quality(
+                    rocket::tokio::io::BufReader::new(body),
+                    level,
+                );
+
+                CompressionUtils::set_body_and_encoding(response, compressor, Encoding::Brotli);
+            }
+            Encoding::Gzip => {
+                let compressor = async_compression::tokio::bufread::GzipEncoder::with_quality(
+                    rocket::tokio::io::BufReader::new(body),
+                    level,
+                );
+
+                CompressionUtils::set_body_and_encoding(response, compressor, Encoding::Gzip);
+            }
+            Encoding::Deflate => {
+                let compressor = async_compression::tokio::bufread::ZlibEncoder::with_quality(
+                    rocket::tokio::io::BufReader::new(body),
+                    level,
+                );
+
+                CompressionUtils::set_body_and_encoding(response, compressor, Encoding::Deflate);
+            }
+            _ => unreachable!("best_encoding only returns encodings the server can produce"),
         }
+
+        CompressionUtils::add_vary_header(response);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn best_encoding_for(values: &[&str]) -> Encoding {
+        let accepted = CompressionUtils::parse_accept_encoding_values(values.iter().copied());
+        CompressionUtils::best_encoding_for(accepted)
+    }
+
+    #[test]
+    fn q_zero_refuses_a_coding() {
+        assert!(matches!(
+            best_encoding_for(&["gzip;q=0"]),
+            Encoding::Identity
+        ));
+    }
+
+    #[test]
+    fn q_zero_on_wildcard_refuses_everything_else() {
+        assert!(matches!(
+            best_encoding_for(&["gzip;q=0.5", "*;q=0"]),
+            Encoding::Gzip
+        ));
+    }
+
+    #[test]
+    fn wildcard_is_used_as_fallback() {
+        #[cfg(feature = "zstd")]
+        assert!(matches!(best_encoding_for(&["*"]), Encoding::Zstd));
+        #[cfg(not(feature = "zstd"))]
+        assert!(matches!(best_encoding_for(&["*"]), Encoding::Brotli));
+    }
+
+    #[test]
+    fn explicit_quality_beats_wildcard() {
+        assert!(matches!(
+            best_encoding_for(&["*;q=0.1", "gzip;q=0.9"]),
+            Encoding::Gzip
+        ));
+    }
+
+    #[test]
+    fn ties_are_broken_by_server_preference_order() {
+        // br and gzip are equally preferred by the client; the server's
+        // fixed tie-break order (zstd, br, gzip, deflate) picks brotli.
+        assert!(matches!(
+            best_encoding_for(&["gzip;q=1.0", "br;q=1.0"]),
+            Encoding::Brotli
+        ));
+    }
+
+    #[test]
+    fn no_acceptable_coding_falls_back_to_identity() {
+        assert!(matches!(best_encoding_for(&[]), Encoding::Identity));
     }
 }
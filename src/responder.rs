@@ -0,0 +1,66 @@
+//! A [`Responder`](rocket::response::Responder) that forces compression of a
+//! single response.
+
+use rocket::{
+    request::Request,
+    response::{self, Responder, Response},
+};
+
+use crate::{CompressionUtils, Encoding};
+
+/// Wraps a [`Responder`] and compresses its response according to the
+/// request's `Accept-Encoding` header, regardless of the response's content
+/// type or of any exclusions configured on the [`Compression`](crate::Compression)
+/// fairing.
+///
+/// Useful for forcing compression of a specific response that the fairing
+/// would otherwise skip.
+pub struct Compress<R>(pub R);
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for Compress<R> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'o> {
+        let mut response = self.0.respond_to(request)?;
+
+        if CompressionUtils::already_encoded(&response) {
+            return Ok(response);
+        }
+
+        let encoding = CompressionUtils::best_encoding(request);
+        let body = response.body_mut().take();
+
+        match encoding {
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => {
+                let compressor = async_compression::tokio::bufread::ZstdEncoder::with_quality(
+                    rocket::tokio::io::BufReader::new(body),
+                    async_compression::Level::Default,
+                );
+                CompressionUtils::set_body_and_encoding(&mut response, compressor, Encoding::Zstd);
+            }
+            Encoding::Brotli => {
+                let compressor = async_compression::tokio::bufread::BrotliEncoder::with_quality(
+                    rocket::tokio::io::BufReader::new(body),
+                    async_compression::Level::Default,
+                );
+                CompressionUtils::set_body_and_encoding(&mut response, compressor, Encoding::Brotli);
+            }
+            Encoding::Gzip => {
+                let compressor = async_compression::tokio::bufread::GzipEncoder::with_quality(
+                    rocket::tokio::io::BufReader::new(body),
+                    async_compression::Level::Default,
+                );
+                CompressionUtils::set_body_and_encoding(&mut response, compressor, Encoding::Gzip);
+            }
+            Encoding::Deflate => {
+                let compressor = async_compression::tokio::bufread::ZlibEncoder::with_quality(
+                    rocket::tokio::io::BufReader::new(body),
+                    async_compression::Level::Default,
+                );
+                CompressionUtils::set_body_and_encoding(&mut response, compressor, Encoding::Deflate);
+            }
+            _ => response.set_streamed_body(body),
+        }
+
+        Ok(response)
+    }
+}